@@ -0,0 +1,211 @@
+use anyhow::{bail, Context, Error, Result};
+use chrono::prelude::*;
+use std::io::BufRead;
+
+use crate::gpx;
+use crate::OptionExt;
+
+/// Acceleration due to gravity, in units of g (the `.acc` samples are already
+/// g-relative, so resting on a level surface reads magnitude 1.0).
+const GRAVITY_G: f64 = 1.0;
+
+/// How far the sample magnitude has to deviate from 1g before we call it an event.
+const EVENT_THRESHOLD_G: f64 = 0.4;
+
+#[derive(Debug)]
+pub enum AccRecord {
+    User(String),
+    Version(String),
+    AppVersion(String),
+    Device(Vec<String>),
+    Sample {
+        timestamp: DateTime<FixedOffset>,
+        x: f64, // g
+        y: f64, // g
+        z: f64, // g
+    },
+}
+
+pub fn parse(reader: impl BufRead) -> Result<Vec<AccRecord>> {
+    let mut records = vec![];
+    for line in reader.lines() {
+        let line = line.context("read error")?;
+        let mut fields = line.split(',');
+        let tag = fields.next().ok_or_else(|| Error::msg("missing tag field"))?;
+        macro_rules! parse {
+            ($name:expr) => {
+                fields.next().named($name)?.parse().context(concat!("invalid ", $name))?
+            }
+        }
+        let record = match tag {
+            "U" => AccRecord::User(fields.next().named("username")?.to_owned()),
+            "V" => AccRecord::Version(fields.next().named("version")?.to_owned()),
+            "A" => AccRecord::AppVersion(fields.next().named("app version")?.to_owned()),
+            "I" => AccRecord::Device(fields.map(|s| s.to_owned()).collect()),
+            "G" => {
+                let utc_timestamp: i64 = parse!("timestamp"); // UTC millis
+                let x = parse!("x");
+                let y = parse!("y");
+                let z = parse!("z");
+                let timestamp = chrono::FixedOffset::east(0).timestamp(
+                    utc_timestamp / 1000,
+                    (utc_timestamp % 1000) as u32 * 1_000_000);
+
+                AccRecord::Sample { timestamp, x, y, z }
+            }
+            _ => bail!("unrecognized tag {} in .acc file", tag),
+        };
+        records.push(record);
+    }
+    Ok(records)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccelEvent {
+    HardBraking,
+    SuddenAcceleration,
+}
+
+impl AccelEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AccelEvent::HardBraking => "hard-braking",
+            AccelEvent::SuddenAcceleration => "sudden-acceleration",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AccelSample {
+    pub timestamp: DateTime<FixedOffset>,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct FusedPoint {
+    pub point: gpx::Point,
+    pub accel: Option<AccelSample>,
+    pub event: Option<AccelEvent>,
+}
+
+fn samples_from_records(records: &[AccRecord]) -> Vec<AccelSample> {
+    records.iter()
+        .filter_map(|r| match r {
+            AccRecord::Sample { timestamp, x, y, z } =>
+                Some(AccelSample { timestamp: *timestamp, x: *x, y: *y, z: *z }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Find the accelerometer sample whose timestamp is closest to `t`.
+///
+/// `samples` is assumed to be in non-decreasing timestamp order, as produced
+/// by reading the `.acc` file front to back.
+fn nearest_sample(samples: &[AccelSample], t: DateTime<FixedOffset>) -> Option<AccelSample> {
+    let idx = samples.partition_point(|s| s.timestamp < t);
+    let mut best: Option<(usize, i64)> = None;
+    for i in [idx.checked_sub(1), Some(idx)].into_iter().flatten() {
+        if i >= samples.len() {
+            continue;
+        }
+        let diff = (samples[i].timestamp - t).num_milliseconds().abs();
+        if best.is_none_or(|(_, best_diff)| diff < best_diff) {
+            best = Some((i, diff));
+        }
+    }
+    best.map(|(i, _)| samples[i].clone())
+}
+
+/// Detect hard-braking / sudden-acceleration from a sample's magnitude minus
+/// gravity, the way dedicated GPS tracker firmware reports combined-G events.
+fn detect_event(sample: &AccelSample) -> Option<AccelEvent> {
+    let magnitude = (sample.x * sample.x + sample.y * sample.y + sample.z * sample.z).sqrt();
+    let delta = magnitude - GRAVITY_G;
+    if delta.abs() < EVENT_THRESHOLD_G {
+        return None;
+    }
+    if sample.x < 0.0 {
+        Some(AccelEvent::HardBraking)
+    } else {
+        Some(AccelEvent::SuddenAcceleration)
+    }
+}
+
+/// Fuse accelerometer samples onto the reconstructed GPS timeline by
+/// nearest-timestamp matching, annotating each point with any detected event.
+pub fn fuse(points: &[gpx::Point], acc_records: &[AccRecord]) -> Vec<FusedPoint> {
+    let samples = samples_from_records(acc_records);
+    points.iter()
+        .map(|point| {
+            let accel = nearest_sample(&samples, point.time);
+            let event = accel.as_ref().and_then(detect_event);
+            FusedPoint { point: point.clone(), accel, event }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts(secs: i64, millis: i64) -> DateTime<FixedOffset> {
+        chrono::FixedOffset::east(0).timestamp(secs, (millis * 1_000_000) as u32)
+    }
+
+    fn sample(secs: i64, millis: i64, x: f64, y: f64, z: f64) -> AccelSample {
+        AccelSample { timestamp: ts(secs, millis), x, y, z }
+    }
+
+    fn point(secs: i64, millis: i64) -> gpx::Point {
+        gpx::Point {
+            time: ts(secs, millis),
+            lat: 0., lon: 0., ele: 0., speed: 0., course: 0.,
+        }
+    }
+
+    #[test]
+    fn nearest_sample_picks_closer_neighbor() {
+        let samples = vec![sample(0, 0, 0., 0., 1.), sample(10, 0, 0., 0., 1.)];
+        let found = nearest_sample(&samples, ts(2, 0)).unwrap();
+        assert_eq!(found.timestamp, ts(0, 0));
+
+        let found = nearest_sample(&samples, ts(9, 0)).unwrap();
+        assert_eq!(found.timestamp, ts(10, 0));
+    }
+
+    #[test]
+    fn nearest_sample_empty_is_none() {
+        assert!(nearest_sample(&[], ts(0, 0)).is_none());
+    }
+
+    #[test]
+    fn detect_event_resting_is_none() {
+        let s = sample(0, 0, 0., 0., 1.0); // 1g on the z axis, nothing else
+        assert_eq!(detect_event(&s), None);
+    }
+
+    #[test]
+    fn detect_event_hard_braking_on_negative_x() {
+        let s = sample(0, 0, -1.0, 0., 1.0); // magnitude sqrt(2) ~= 1.41g
+        assert_eq!(detect_event(&s), Some(AccelEvent::HardBraking));
+    }
+
+    #[test]
+    fn detect_event_sudden_acceleration_on_positive_x() {
+        let s = sample(0, 0, 1.0, 0., 1.0);
+        assert_eq!(detect_event(&s), Some(AccelEvent::SuddenAcceleration));
+    }
+
+    #[test]
+    fn fuse_attaches_nearest_sample_and_event() {
+        let points = vec![point(0, 0)];
+        let acc_records = vec![AccRecord::Sample { timestamp: ts(0, 0), x: -1.0, y: 0., z: 1.0 }];
+        let fused = fuse(&points, &acc_records);
+        assert_eq!(fused.len(), 1);
+        assert_eq!(fused[0].event, Some(AccelEvent::HardBraking));
+        assert!(fused[0].accel.is_some());
+    }
+}
@@ -0,0 +1,186 @@
+use anyhow::{bail, Result};
+use chrono::SecondsFormat;
+use std::io::Write;
+
+use crate::acc::FusedPoint;
+use crate::gpx;
+
+/// A track output backend, selected on the command line via `--format`.
+pub trait TrackWriter {
+    fn extension(&self) -> &'static str;
+    fn write(&self, out: &mut dyn Write, segments: &[&[FusedPoint]]) -> Result<()>;
+}
+
+pub struct Gpx;
+pub struct GeoJson;
+pub struct Kml;
+
+impl TrackWriter for Gpx {
+    fn extension(&self) -> &'static str { "gpx" }
+    fn write(&self, out: &mut dyn Write, segments: &[&[FusedPoint]]) -> Result<()> {
+        gpx::write_gpx(out, segments)
+    }
+}
+
+impl TrackWriter for GeoJson {
+    fn extension(&self) -> &'static str { "geojson" }
+    fn write(&self, out: &mut dyn Write, segments: &[&[FusedPoint]]) -> Result<()> {
+        write_geojson(out, segments)
+    }
+}
+
+impl TrackWriter for Kml {
+    fn extension(&self) -> &'static str { "kml" }
+    fn write(&self, out: &mut dyn Write, segments: &[&[FusedPoint]]) -> Result<()> {
+        write_kml(out, segments)
+    }
+}
+
+pub fn by_name(name: &str) -> Result<Box<dyn TrackWriter>> {
+    Ok(match name {
+        "gpx" => Box::new(Gpx),
+        "geojson" => Box::new(GeoJson),
+        "kml" => Box::new(Kml),
+        other => bail!("unrecognized output format {}", other),
+    })
+}
+
+fn write_geojson(mut out: impl Write, segments: &[&[FusedPoint]]) -> Result<()> {
+    writeln!(out, "{{")?;
+    writeln!(out, "  \"type\": \"FeatureCollection\",")?;
+    writeln!(out, "  \"features\": [")?;
+    for (i, segment) in segments.iter().enumerate() {
+        writeln!(out, "    {{")?;
+        writeln!(out, "      \"type\": \"Feature\",")?;
+        writeln!(out, "      \"geometry\": {{")?;
+        writeln!(out, "        \"type\": \"LineString\",")?;
+        write!(out, "        \"coordinates\": [")?;
+        for (j, fp) in segment.iter().enumerate() {
+            if j > 0 { write!(out, ", ")?; }
+            write!(out, "[{}, {}, {}]", fp.point.lon, fp.point.lat, fp.point.ele)?;
+        }
+        writeln!(out, "]")?;
+        writeln!(out, "      }},")?;
+        writeln!(out, "      \"properties\": {{")?;
+        write!(out, "        \"time\": [")?;
+        for (j, fp) in segment.iter().enumerate() {
+            if j > 0 { write!(out, ", ")?; }
+            write!(out, "\"{}\"", fp.point.time.to_rfc3339_opts(SecondsFormat::Secs, true))?;
+        }
+        writeln!(out, "],")?;
+        write!(out, "        \"speed\": [")?;
+        for (j, fp) in segment.iter().enumerate() {
+            if j > 0 { write!(out, ", ")?; }
+            write!(out, "{}", fp.point.speed)?;
+        }
+        writeln!(out, "],")?;
+        write!(out, "        \"course\": [")?;
+        for (j, fp) in segment.iter().enumerate() {
+            if j > 0 { write!(out, ", ")?; }
+            write!(out, "{}", fp.point.course)?;
+        }
+        writeln!(out, "],")?;
+        write!(out, "        \"event\": [")?;
+        for (j, fp) in segment.iter().enumerate() {
+            if j > 0 { write!(out, ", ")?; }
+            match fp.event {
+                Some(event) => write!(out, "\"{}\"", event.as_str())?,
+                None => write!(out, "null")?,
+            }
+        }
+        writeln!(out, "]")?;
+        writeln!(out, "      }}")?;
+        write!(out, "    }}")?;
+        writeln!(out, "{}", if i + 1 < segments.len() { "," } else { "" })?;
+    }
+    writeln!(out, "  ]")?;
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+fn write_kml(mut out: impl Write, segments: &[&[FusedPoint]]) -> Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<kml xmlns="http://www.opengis.net/kml/2.2" xmlns:gx="http://www.google.com/kml/ext/2.2">"#)?;
+    writeln!(out, "  <Document>")?;
+    for segment in segments {
+        writeln!(out, "    <Placemark>")?;
+        writeln!(out, "      <gx:Track>")?;
+        for fp in *segment {
+            writeln!(out, "        <when>{}</when>", fp.point.time.to_rfc3339_opts(SecondsFormat::Secs, true))?;
+        }
+        for fp in *segment {
+            writeln!(out, "        <gx:coord>{} {} {}</gx:coord>", fp.point.lon, fp.point.lat, fp.point.ele)?;
+        }
+        writeln!(out, "      </gx:Track>")?;
+        if segment.iter().any(|fp| fp.event.is_some()) {
+            writeln!(out, "      <ExtendedData>")?;
+            for (i, fp) in segment.iter().enumerate() {
+                if let Some(event) = fp.event {
+                    writeln!(out, r#"        <Data name="event_{}"><value>{}</value></Data>"#, i, event.as_str())?;
+                }
+            }
+            writeln!(out, "      </ExtendedData>")?;
+        }
+        writeln!(out, "    </Placemark>")?;
+    }
+    writeln!(out, "  </Document>")?;
+    writeln!(out, "</kml>")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acc::AccelEvent;
+    use chrono::prelude::*;
+
+    fn fused_point(secs: i64, event: Option<AccelEvent>) -> FusedPoint {
+        FusedPoint {
+            point: gpx::Point {
+                time: Utc.timestamp(secs, 0).into(),
+                lat: 1., lon: 2., ele: 3., speed: 4., course: 5.,
+            },
+            accel: None,
+            event,
+        }
+    }
+
+    #[test]
+    fn by_name_resolves_known_formats_and_rejects_others() {
+        assert_eq!(by_name("gpx").unwrap().extension(), "gpx");
+        assert_eq!(by_name("geojson").unwrap().extension(), "geojson");
+        assert_eq!(by_name("kml").unwrap().extension(), "kml");
+        assert!(by_name("nope").is_err());
+    }
+
+    #[test]
+    fn write_geojson_includes_coordinates_and_events() {
+        let points = vec![fused_point(0, Some(AccelEvent::HardBraking)), fused_point(1, None)];
+        let mut out = vec![];
+        write_geojson(&mut out, &[&points]).unwrap();
+        let json = String::from_utf8(out).unwrap();
+        assert!(json.contains("\"FeatureCollection\""));
+        assert!(json.contains("[2, 1, 3]"));
+        assert!(json.contains("\"hard-braking\""));
+        assert!(json.contains("null"));
+    }
+
+    #[test]
+    fn write_kml_omits_extended_data_when_no_events() {
+        let points = vec![fused_point(0, None)];
+        let mut out = vec![];
+        write_kml(&mut out, &[&points]).unwrap();
+        let kml = String::from_utf8(out).unwrap();
+        assert!(!kml.contains("ExtendedData"));
+    }
+
+    #[test]
+    fn write_kml_includes_extended_data_when_event_present() {
+        let points = vec![fused_point(0, Some(AccelEvent::SuddenAcceleration))];
+        let mut out = vec![];
+        write_kml(&mut out, &[&points]).unwrap();
+        let kml = String::from_utf8(out).unwrap();
+        assert!(kml.contains("ExtendedData"));
+        assert!(kml.contains("sudden-acceleration"));
+    }
+}
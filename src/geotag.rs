@@ -0,0 +1,498 @@
+use anyhow::{bail, Context, Result};
+use chrono::prelude::*;
+use std::path::Path;
+
+use crate::gpx::Point;
+
+// Well-known TIFF/Exif tags we care about.
+const TAG_EXIF_IFD_POINTER: u16 = 0x8769;
+const TAG_GPS_IFD_POINTER: u16 = 0x8825;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+
+const TYPE_BYTE: u16 = 1;
+const TYPE_ASCII: u16 = 2;
+const TYPE_SHORT: u16 = 3;
+const TYPE_LONG: u16 = 4;
+const TYPE_RATIONAL: u16 = 5;
+
+/// How far (in seconds) a photo's timestamp may fall outside the track, or
+/// away from its nearest point, before we give up geotagging it.
+#[derive(Debug, Clone, Copy)]
+pub struct Tolerance(pub chrono::Duration);
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Tolerance(chrono::Duration::seconds(300))
+    }
+}
+
+/// An unsigned rational, as used throughout the Exif GPS IFD.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Rational(u32, u32);
+
+impl Rational {
+    fn from_degrees(mut deg: f64) -> [Rational; 3] {
+        if deg < 0. {
+            deg = -deg;
+        }
+        let degrees = deg.trunc();
+        let rem = (deg - degrees) * 60.;
+        let minutes = rem.trunc();
+        let seconds = (rem - minutes) * 60.;
+        [
+            Rational(degrees as u32, 1),
+            Rational(minutes as u32, 1),
+            Rational((seconds * 1000.).round() as u32, 1000),
+        ]
+    }
+}
+
+/// Geotag every JPEG in `dir`, writing GPS Exif tags interpolated from `points`.
+pub fn geotag_directory(dir: &Path, points: &[Point], tolerance: Tolerance) -> Result<()> {
+    if points.is_empty() {
+        bail!("no points to geotag from");
+    }
+    let mut sorted = points.to_vec();
+    sorted.sort_by_key(|p| p.time);
+
+    for entry in std::fs::read_dir(dir).context("failed to read photo directory")? {
+        let entry = entry?;
+        let path = entry.path();
+        let is_jpeg = path.extension()
+            .map(|ext| ext.eq_ignore_ascii_case("jpg") || ext.eq_ignore_ascii_case("jpeg"))
+            .unwrap_or(false);
+        if !is_jpeg {
+            continue;
+        }
+        match geotag_photo(&path, &sorted, tolerance) {
+            Ok(true) => println!("geotagged {}", path.display()),
+            Ok(false) => println!("skipping {} (outside tolerance or no timestamp)", path.display()),
+            Err(e) => println!("failed to geotag {}: {:#}", path.display(), e),
+        }
+    }
+    Ok(())
+}
+
+/// Geotag a single photo. Returns `Ok(false)` if it was skipped (no usable
+/// `DateTimeOriginal`, or too far from the track to interpolate).
+fn geotag_photo(path: &Path, sorted_points: &[Point], tolerance: Tolerance) -> Result<bool> {
+    let data = std::fs::read(path).context("failed to read photo")?;
+    let Some(app1) = find_app1(&data) else {
+        return Ok(false);
+    };
+    let Some(tiff) = Tiff::new(app1) else {
+        return Ok(false);
+    };
+    let Some(taken_at) = read_date_time_original(app1) else {
+        return Ok(false);
+    };
+
+    let Some((lat, lon, ele)) = interpolate(sorted_points, taken_at, tolerance) else {
+        return Ok(false);
+    };
+
+    let gps_ifd = build_gps_ifd(lat, lon, ele, taken_at, tiff.big_endian);
+    let patched = patch_app1_with_gps_ifd(app1, &gps_ifd)?;
+    let new_data = splice_app1(&data, patched);
+    std::fs::write(path, new_data).context("failed to write geotagged photo")?;
+    Ok(true)
+}
+
+/// Binary-search the bracketing points for `t` and linearly interpolate
+/// lat/lon/ele, clamping to the endpoints outside the track.
+fn interpolate(sorted_points: &[Point], t: DateTime<FixedOffset>, tolerance: Tolerance) -> Option<(f64, f64, f64)> {
+    let idx = sorted_points.partition_point(|p| p.time < t);
+    if idx == 0 {
+        let p0 = &sorted_points[0];
+        return ((p0.time - t).abs() <= tolerance.0).then_some((p0.lat, p0.lon, p0.ele));
+    }
+    if idx == sorted_points.len() {
+        let p1 = &sorted_points[sorted_points.len() - 1];
+        return ((t - p1.time).abs() <= tolerance.0).then_some((p1.lat, p1.lon, p1.ele));
+    }
+    let p0 = &sorted_points[idx - 1];
+    let p1 = &sorted_points[idx];
+    if p0.time == p1.time {
+        return Some((p0.lat, p0.lon, p0.ele));
+    }
+    if std::cmp::min(t - p0.time, p1.time - t) > tolerance.0 {
+        return None;
+    }
+    let span = (p1.time - p0.time).num_milliseconds() as f64;
+    let f = (t - p0.time).num_milliseconds() as f64 / span;
+    Some((
+        p0.lat + (p1.lat - p0.lat) * f,
+        p0.lon + (p1.lon - p0.lon) * f,
+        p0.ele + (p1.ele - p0.ele) * f,
+    ))
+}
+
+// ---- minimal hand-rolled Exif/TIFF reading & writing ----
+
+/// Find the Exif APP1 segment in a JPEG, returning its TIFF payload
+/// (everything after the `Exif\0\0` header).
+fn find_app1(data: &[u8]) -> Option<&[u8]> {
+    if data.len() < 4 || data[0..2] != [0xFF, 0xD8] {
+        return None;
+    }
+    let mut pos = 2;
+    while pos + 4 <= data.len() {
+        let marker = u16::from_be_bytes([data[pos], data[pos + 1]]);
+        if marker == 0xFFDA {
+            break; // start of scan: no more markers to look at
+        }
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let seg_start = pos + 4;
+        let seg_end = pos + 2 + len;
+        if seg_start > seg_end || seg_end > data.len() {
+            return None; // truncated/corrupt segment length
+        }
+        if marker == 0xFFE1 && data[seg_start..].starts_with(b"Exif\0\0") {
+            return data.get(seg_start + 6..seg_end);
+        }
+        pos = seg_end;
+    }
+    None
+}
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let big_endian = match &data[0..2] {
+            b"II" => false,
+            b"MM" => true,
+            _ => return None,
+        };
+        Some(Tiff { data, big_endian })
+    }
+
+    /// Returns `None` on a truncated/malformed blob instead of panicking, since
+    /// a partially-copied or corrupted photo is a real input, not just an
+    /// adversarial one.
+    fn u16(&self, off: usize) -> Option<u16> {
+        let b = self.data.get(off..off + 2)?;
+        Some(if self.big_endian { u16::from_be_bytes([b[0], b[1]]) } else { u16::from_le_bytes([b[0], b[1]]) })
+    }
+
+    fn u32(&self, off: usize) -> Option<u32> {
+        let b: [u8; 4] = self.data.get(off..off + 4)?.try_into().ok()?;
+        Some(if self.big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    }
+
+    /// Entries of the IFD at `ifd_off`, as (tag, type, count, value/offset field).
+    fn entries(&self, ifd_off: usize) -> Option<Vec<(u16, u16, u32, usize)>> {
+        let count = self.u16(ifd_off)? as usize;
+        (0..count)
+            .map(|i| {
+                let entry_off = ifd_off + 2 + i * 12;
+                Some((self.u16(entry_off)?, self.u16(entry_off + 2)?, self.u32(entry_off + 4)?, entry_off + 8))
+            })
+            .collect()
+    }
+}
+
+fn read_date_time_original(tiff_data: &[u8]) -> Option<DateTime<FixedOffset>> {
+    let tiff = Tiff::new(tiff_data)?;
+    let ifd0_off = tiff.u32(4)? as usize;
+    let ifd0 = tiff.entries(ifd0_off)?;
+    let (_, _, _, exif_ifd_field) = ifd0.iter().find(|(tag, ..)| *tag == TAG_EXIF_IFD_POINTER)?;
+    let exif_ifd_off = tiff.u32(*exif_ifd_field)? as usize;
+    let exif_ifd = tiff.entries(exif_ifd_off)?;
+    let (_, _, count, field) = exif_ifd.iter().find(|(tag, ..)| *tag == TAG_DATE_TIME_ORIGINAL)?;
+    let str_off = if *count <= 4 { *field } else { tiff.u32(*field)? as usize };
+    let bytes = tiff.data.get(str_off..str_off + *count as usize)?;
+    let s = std::str::from_utf8(bytes).ok()?.trim_end_matches('\0');
+    // Exif dates have no timezone; treat as UTC like the rest of the GPS pipeline.
+    let naive = NaiveDateTime::parse_from_str(s, "%Y:%m:%d %H:%M:%S").ok()?;
+    Some(DateTime::<Utc>::from_utc(naive, Utc).into())
+}
+
+/// Serialized GPS IFD: entry table plus any out-of-line data (the rationals).
+struct GpsIfd {
+    entries: Vec<u8>,
+    entry_count: u16,
+    extra: Vec<u8>,
+}
+
+fn build_gps_ifd(lat: f64, lon: f64, ele: f64, time: DateTime<FixedOffset>, big_endian: bool) -> GpsIfd {
+    let mut entries = vec![];
+    let mut extra = vec![];
+    let mut entry_count = 0u16;
+
+    let u16_bytes = |v: u16| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+    let u32_bytes = |v: u32| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+
+    // Entry offsets into `extra` are relative; patch_app1_with_gps_ifd() rebases them.
+    macro_rules! push_entry {
+        ($tag:expr, $type:expr, $count:expr, $value:expr) => {{
+            entries.extend_from_slice(&u16_bytes($tag));
+            entries.extend_from_slice(&u16_bytes($type as u16));
+            entries.extend_from_slice(&u32_bytes($count as u32));
+            entries.extend_from_slice(&$value);
+            entry_count += 1;
+        }};
+    }
+    macro_rules! push_rationals_entry {
+        ($tag:expr, $rationals:expr) => {{
+            let offset = extra.len() as u32;
+            for r in &$rationals {
+                extra.extend_from_slice(&u32_bytes(r.0));
+                extra.extend_from_slice(&u32_bytes(r.1));
+            }
+            push_entry!($tag, TYPE_RATIONAL, $rationals.len(), u32_bytes(offset));
+        }};
+    }
+
+    let lat_ref = if lat >= 0. { b"N\0\0\0" } else { b"S\0\0\0" };
+    let lon_ref = if lon >= 0. { b"E\0\0\0" } else { b"W\0\0\0" };
+    push_entry!(1u16, TYPE_ASCII, 2, *lat_ref); // GPSLatitudeRef
+    push_rationals_entry!(2u16, Rational::from_degrees(lat)); // GPSLatitude
+    push_entry!(3u16, TYPE_ASCII, 2, *lon_ref); // GPSLongitudeRef
+    push_rationals_entry!(4u16, Rational::from_degrees(lon)); // GPSLongitude
+
+    let (alt_ref, alt) = if ele >= 0. { (0u8, ele) } else { (1u8, -ele) };
+    push_entry!(5u16, TYPE_BYTE, 1, [alt_ref, 0, 0, 0]); // GPSAltitudeRef
+    push_rationals_entry!(6u16, [Rational((alt * 100.).round() as u32, 100)]); // GPSAltitude
+
+    let utc = time.with_timezone(&Utc);
+    push_rationals_entry!(
+        7u16, // GPSTimeStamp
+        [
+            Rational(utc.hour(), 1),
+            Rational(utc.minute(), 1),
+            Rational(utc.second(), 1),
+        ]
+    );
+    let date_stamp = format!("{}\0", utc.format("%Y:%m:%d"));
+    let offset = extra.len() as u32;
+    extra.extend_from_slice(date_stamp.as_bytes());
+    push_entry!(29u16, TYPE_ASCII, date_stamp.len(), u32_bytes(offset)); // GPSDateStamp
+
+    GpsIfd { entries, entry_count, extra }
+}
+
+/// Rebuild the TIFF payload with an added GPS IFD, relocating every absolute
+/// offset that now falls after the insertion point by the size we grew IFD0 by.
+fn patch_app1_with_gps_ifd(tiff_data: &[u8], gps: &GpsIfd) -> Result<Vec<u8>> {
+    let tiff = Tiff::new(tiff_data).context("not a valid TIFF/Exif segment")?;
+    let ifd0_off = tiff.u32(4).context("truncated TIFF header")? as usize;
+    let ifd0_entry_count = tiff.u16(ifd0_off).context("truncated IFD0")? as usize;
+    let ifd0_entries_end = ifd0_off + 2 + ifd0_entry_count * 12;
+
+    // We insert one 12-byte entry into IFD0 (the GPS IFD pointer); everything
+    // from that point on shifts forward by 12 bytes. Relocate offsets on a
+    // full copy first, using the *original* (pre-insertion) layout to find
+    // every IFD, then splice the new entry in after IFD0's relocated entries.
+    let insert_at = ifd0_entries_end;
+    let delta = 12i64;
+    let big_endian = tiff.big_endian;
+    let u16_bytes = |v: u16| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+    let u32_bytes = |v: u32| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+
+    let mut relocated = tiff_data.to_vec();
+    relocate_offsets(&mut relocated, insert_at, delta, big_endian)?;
+
+    let mut out = Vec::with_capacity(tiff_data.len() + 12 + 2 + gps.entries.len() + gps.extra.len());
+    out.extend_from_slice(&tiff_data[..ifd0_off]);
+    out.extend_from_slice(&u16_bytes(ifd0_entry_count as u16 + 1));
+    out.extend_from_slice(&relocated[ifd0_off + 2..insert_at]);
+
+    // The GPS IFD itself is appended at the very end of the (shifted) file,
+    // so its offset is the new total length once we know it.
+    let gps_ifd_offset = (tiff_data.len() as i64 + delta) as u32;
+    out.extend_from_slice(&u16_bytes(TAG_GPS_IFD_POINTER));
+    out.extend_from_slice(&u16_bytes(TYPE_LONG));
+    out.extend_from_slice(&u32_bytes(1u32));
+    out.extend_from_slice(&u32_bytes(gps_ifd_offset));
+
+    out.extend_from_slice(&relocated[insert_at..]);
+
+    out.extend_from_slice(&u16_bytes(gps.entry_count));
+    let gps_entries_start = out.len();
+    out.extend_from_slice(&gps.entries);
+    out.extend_from_slice(&u32_bytes(0u32)); // no next IFD
+    let extra_offset_base = out.len() as u32;
+    rebase_extra_offsets(&mut out, gps_entries_start, gps.entry_count, extra_offset_base, big_endian)?;
+    out.extend_from_slice(&gps.extra);
+
+    Ok(out)
+}
+
+/// Walk every IFD in `data` (IFD0, Exif/GPS/Interop sub-IFDs, thumbnail IFD
+/// chain) and add `delta` to any absolute offset field that points at or past
+/// `threshold`, since we shifted everything from `threshold` onward forward.
+/// Fails instead of panicking if a corrupt offset points outside `data`.
+fn relocate_offsets(data: &mut [u8], threshold: usize, delta: i64, big_endian: bool) -> Result<()> {
+    let read_u32 = |d: &[u8], o: usize| -> Result<u32> {
+        let b: [u8; 4] = d.get(o..o + 4).context("offset points outside TIFF data")?.try_into().unwrap();
+        Ok(if big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    };
+    let write_u32 = |d: &mut [u8], o: usize, v: u32| -> Result<()> {
+        let slice = d.get_mut(o..o + 4).context("offset points outside TIFF data")?;
+        let b = if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+        slice.copy_from_slice(&b);
+        Ok(())
+    };
+    let read_u16 = |d: &[u8], o: usize| -> Result<u16> {
+        let b: [u8; 2] = d.get(o..o + 2).context("offset points outside TIFF data")?.try_into().unwrap();
+        Ok(if big_endian { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) })
+    };
+
+    // Queue of IFD offsets in the original layout; IFD0's lives at TIFF header + 4.
+    let mut ifd_offsets = vec![read_u32(data, 4)? as usize];
+    let mut i = 0;
+    while i < ifd_offsets.len() {
+        let ifd_off = ifd_offsets[i];
+        i += 1;
+        if ifd_off == 0 {
+            continue;
+        }
+        let count = read_u16(data, ifd_off)? as usize;
+        for e in 0..count {
+            let entry_off = ifd_off + 2 + e * 12;
+            let tag = read_u16(data, entry_off)?;
+            let ty = read_u16(data, entry_off + 2)?;
+            let cnt = read_u32(data, entry_off + 4)? as usize;
+            let value_field = entry_off + 8;
+            let size = type_size(ty) * cnt;
+            let is_pointer_tag = matches!(tag, TAG_EXIF_IFD_POINTER | TAG_GPS_IFD_POINTER | 0xA005);
+            if size > 4 || is_pointer_tag {
+                let v = read_u32(data, value_field)?;
+                if is_pointer_tag {
+                    ifd_offsets.push(v as usize);
+                }
+                if v as usize >= threshold {
+                    write_u32(data, value_field, (v as i64 + delta) as u32)?;
+                }
+            }
+        }
+        let next_field = ifd_off + 2 + count * 12;
+        let next_ifd = read_u32(data, next_field)?;
+        if next_ifd != 0 {
+            if next_ifd as usize >= threshold {
+                write_u32(data, next_field, (next_ifd as i64 + delta) as u32)?;
+            }
+            ifd_offsets.push(next_ifd as usize);
+        }
+    }
+    Ok(())
+}
+
+fn type_size(ty: u16) -> usize {
+    match ty {
+        TYPE_BYTE | TYPE_ASCII => 1,
+        TYPE_SHORT => 2,
+        TYPE_LONG => 4,
+        TYPE_RATIONAL => 8,
+        _ => 4,
+    }
+}
+
+/// The GPS IFD's out-of-line rational/ASCII data lives in `extra`, addressed
+/// by entry offsets relative to the start of `extra`; rewrite them to
+/// absolute offsets now that we know where the IFD landed in the file.
+fn rebase_extra_offsets(out: &mut [u8], entries_start: usize, entry_count: u16, extra_offset_base: u32, big_endian: bool) -> Result<()> {
+    let read_u16 = |d: &[u8], o: usize| -> Result<u16> {
+        let b: [u8; 2] = d.get(o..o + 2).context("offset points outside TIFF data")?.try_into().unwrap();
+        Ok(if big_endian { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) })
+    };
+    let read_u32 = |d: &[u8], o: usize| -> Result<u32> {
+        let b: [u8; 4] = d.get(o..o + 4).context("offset points outside TIFF data")?.try_into().unwrap();
+        Ok(if big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    };
+    for e in 0..entry_count as usize {
+        let entry_off = entries_start + e * 12;
+        let ty = read_u16(out, entry_off + 2)?;
+        let cnt = read_u32(out, entry_off + 4)? as usize;
+        if type_size(ty) * cnt > 4 {
+            let value_off = entry_off + 8;
+            let rel = read_u32(out, value_off)?;
+            let abs = extra_offset_base + rel;
+            let bytes = if big_endian { abs.to_be_bytes() } else { abs.to_le_bytes() };
+            out.get_mut(value_off..value_off + 4).context("offset points outside TIFF data")?.copy_from_slice(&bytes);
+        }
+    }
+    Ok(())
+}
+
+/// Splice a patched TIFF payload back into the JPEG as its (only) APP1 segment.
+fn splice_app1(original: &[u8], tiff_payload: Vec<u8>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(original.len() + tiff_payload.len());
+    out.extend_from_slice(&original[0..2]); // SOI
+    let mut header = b"Exif\0\0".to_vec();
+    header.extend_from_slice(&tiff_payload);
+    let seg_len = (header.len() + 2) as u16;
+    out.extend_from_slice(&[0xFF, 0xE1]);
+    out.extend_from_slice(&seg_len.to_be_bytes());
+    out.extend_from_slice(&header);
+
+    // Copy everything else verbatim, skipping the original APP1 if present.
+    let mut pos = 2;
+    while pos + 4 <= original.len() {
+        let marker = u16::from_be_bytes([original[pos], original[pos + 1]]);
+        if marker == 0xFFDA {
+            out.extend_from_slice(&original[pos..]);
+            break;
+        }
+        let len = u16::from_be_bytes([original[pos + 2], original[pos + 3]]) as usize;
+        let seg_end = pos + 2 + len;
+        if marker != 0xFFE1 {
+            out.extend_from_slice(&original[pos..seg_end]);
+        }
+        pos = seg_end;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(time: DateTime<FixedOffset>, lat: f64, lon: f64, ele: f64) -> Point {
+        Point { time, lat, lon, ele, speed: 0., course: 0. }
+    }
+
+    fn utc(secs: i64) -> DateTime<FixedOffset> {
+        Utc.timestamp(secs, 0).into()
+    }
+
+    #[test]
+    fn from_degrees_splits_into_degrees_minutes_seconds() {
+        let [d, m, s] = Rational::from_degrees(45.5075);
+        assert_eq!(d, Rational(45, 1));
+        assert_eq!(m, Rational(30, 1));
+        assert_eq!(s.0 as f64 / s.1 as f64, 27.0, "seconds component");
+    }
+
+    #[test]
+    fn from_degrees_drops_the_sign() {
+        let pos = Rational::from_degrees(12.25);
+        let neg = Rational::from_degrees(-12.25);
+        assert_eq!(pos[0].0, neg[0].0);
+        assert_eq!(pos[1].0, neg[1].0);
+    }
+
+    #[test]
+    fn interpolate_midpoint() {
+        let points = vec![
+            point(utc(0), 0., 0., 0.),
+            point(utc(100), 10., 20., 100.),
+        ];
+        let (lat, lon, ele) = interpolate(&points, utc(50), Tolerance::default()).unwrap();
+        assert_eq!(lat, 5.);
+        assert_eq!(lon, 10.);
+        assert_eq!(ele, 50.);
+    }
+
+    #[test]
+    fn interpolate_clamps_within_tolerance() {
+        let points = vec![point(utc(0), 1., 2., 3.)];
+        let tolerance = Tolerance(chrono::Duration::seconds(10));
+        assert_eq!(interpolate(&points, utc(5), tolerance), Some((1., 2., 3.)));
+        assert_eq!(interpolate(&points, utc(20), tolerance), None);
+    }
+}
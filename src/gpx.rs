@@ -0,0 +1,50 @@
+use anyhow::Result;
+use chrono::prelude::*;
+use std::io::Write;
+
+use crate::acc::FusedPoint;
+
+#[derive(Debug, Clone)]
+pub struct Point {
+    pub time: DateTime<FixedOffset>,
+    pub lat: f64,
+    pub lon: f64,
+    pub ele: f64, // meters
+    pub speed: f64, // meters per second
+    pub course: f64, // degrees
+}
+
+pub fn write_gpx(mut out: impl Write, segments: &[&[FusedPoint]]) -> Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<gpx version="1.1" creator="arz" xmlns="http://www.topografix.com/GPX/1/1">"#)?;
+    writeln!(out, "  <trk>")?;
+    for segment in segments {
+        writeln!(out, "    <trkseg>")?;
+        for fp in *segment {
+            let point = &fp.point;
+            writeln!(out, r#"      <trkpt lat="{}" lon="{}">"#, point.lat, point.lon)?;
+            writeln!(out, "        <ele>{}</ele>", point.ele)?;
+            writeln!(
+                out,
+                "        <time>{}</time>",
+                point.time.to_rfc3339_opts(SecondsFormat::Secs, true))?;
+            writeln!(out, "        <speed>{}</speed>", point.speed)?;
+            writeln!(out, "        <course>{}</course>", point.course)?;
+            if fp.accel.is_some() || fp.event.is_some() {
+                writeln!(out, "        <extensions>")?;
+                if let Some(accel) = &fp.accel {
+                    writeln!(out, r#"          <accel x="{}" y="{}" z="{}"/>"#, accel.x, accel.y, accel.z)?;
+                }
+                if let Some(event) = fp.event {
+                    writeln!(out, "          <event>{}</event>", event.as_str())?;
+                }
+                writeln!(out, "        </extensions>")?;
+            }
+            writeln!(out, "      </trkpt>")?;
+        }
+        writeln!(out, "    </trkseg>")?;
+    }
+    writeln!(out, "  </trk>")?;
+    writeln!(out, "</gpx>")?;
+    Ok(())
+}
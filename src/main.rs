@@ -3,7 +3,13 @@ use chrono::prelude::*;
 use std::fs::File;
 use std::io::BufRead;
 
+mod acc;
+mod formats;
+mod geotag;
 mod gpx;
+mod mp4;
+mod nmea;
+mod track;
 
 trait OptionExt<'a, T: ?Sized> {
     fn named(self, name: &str) -> Result<&'a T>;
@@ -38,34 +44,10 @@ enum GpsRecord {
     },
 }
 
-fn main() -> Result<()> {
-    let path = std::env::args().nth(1).expect("need a file path");
-    let file = File::open(path).context("failed to open file")?;
-    let mut z = zip::ZipArchive::new(file).context("failed to read zip file")?;
-    let (gps_path, _acc_path) = {
-        let mut gps = None;
-        let mut acc = None;
-        for path in z.file_names() {
-            println!("found file {}", path);
-            if path.ends_with(".gps") {
-                gps = Some(path);
-            } else if path.ends_with(".acc") {
-                acc = Some(path);
-            } else {
-                bail!("unrecognized filename {} in input archive", path);
-            }
-        }
-        if gps.is_none() {
-            bail!("missing a .gps file in archive");
-        }
-        if acc.is_none() {
-            bail!("missing a .acc file in archive");
-        }
-        (gps.unwrap().to_owned(), acc.unwrap().to_owned())
-    };
-    let gps_file = z.by_name(&gps_path).context("failed to get .gps file from archive")?;
+/// Parse the proprietary comma-tagged `.gps` format (tag-dispatched per line).
+fn parse_gps_records(reader: impl BufRead) -> Result<Vec<GpsRecord>> {
     let mut records = vec![];
-    for line in std::io::BufReader::new(gps_file).lines() {
+    for line in reader.lines() {
         let line = line.context("read error")?;
         let mut fields = line.split(',');
         let tag = fields.next().ok_or_else(|| Error::msg("missing tag field"))?;
@@ -126,6 +108,91 @@ fn main() -> Result<()> {
         };
         records.push(record);
     }
+    Ok(records)
+}
+
+fn format_duration(d: chrono::Duration) -> String {
+    let total_secs = d.num_seconds();
+    format!("{:02}:{:02}:{:02}", total_secs / 3600, (total_secs / 60) % 60, total_secs % 60)
+}
+
+fn main() -> Result<()> {
+    let mut raw_args: Vec<String> = std::env::args().skip(1).collect();
+    let mut format_name = "gpx".to_owned();
+    if let Some(idx) = raw_args.iter().position(|a| a == "--format") {
+        raw_args.remove(idx);
+        if idx >= raw_args.len() {
+            bail!("--format needs a value");
+        }
+        format_name = raw_args.remove(idx);
+    }
+    let mut geofence: Option<(f64, f64, f64)> = None;
+    if let Some(idx) = raw_args.iter().position(|a| a == "--geofence") {
+        raw_args.remove(idx);
+        if idx >= raw_args.len() {
+            bail!("--geofence needs a value");
+        }
+        let value = raw_args.remove(idx);
+        let mut parts = value.splitn(3, ',');
+        let lat: f64 = parts.next().named("geofence latitude")?.parse().context("invalid geofence latitude")?;
+        let lon: f64 = parts.next().named("geofence longitude")?.parse().context("invalid geofence longitude")?;
+        let radius_m: f64 = parts.next().named("geofence radius")?.parse().context("invalid geofence radius")?;
+        geofence = Some((lat, lon, radius_m));
+    }
+    let mut segment_gap = chrono::Duration::seconds(120);
+    if let Some(idx) = raw_args.iter().position(|a| a == "--segment-gap") {
+        raw_args.remove(idx);
+        if idx >= raw_args.len() {
+            bail!("--segment-gap needs a value");
+        }
+        let secs: i64 = raw_args.remove(idx).parse().context("invalid --segment-gap")?;
+        segment_gap = chrono::Duration::seconds(secs);
+    }
+    let mut args = raw_args.into_iter();
+    let path = args.next().expect("need a file path");
+    let subcommand = args.next();
+
+    let mut first_line = String::new();
+    File::open(&path).context("failed to open file")
+        .and_then(|f| Ok(std::io::BufReader::new(f).read_line(&mut first_line)?))?;
+
+    let (records, acc_records) = if nmea::sniff(&first_line) {
+        println!("detected NMEA 0183 input");
+        let file = File::open(&path).context("failed to open file")?;
+        let records = nmea::parse(std::io::BufReader::new(file))?;
+        (records, vec![])
+    } else {
+        let file = File::open(&path).context("failed to open file")?;
+        let mut z = zip::ZipArchive::new(file).context("failed to read zip file")?;
+        let (gps_path, acc_path) = {
+            let mut gps = None;
+            let mut acc = None;
+            for path in z.file_names() {
+                println!("found file {}", path);
+                if path.ends_with(".gps") {
+                    gps = Some(path);
+                } else if path.ends_with(".acc") {
+                    acc = Some(path);
+                } else {
+                    bail!("unrecognized filename {} in input archive", path);
+                }
+            }
+            if gps.is_none() {
+                bail!("missing a .gps file in archive");
+            }
+            if acc.is_none() {
+                bail!("missing a .acc file in archive");
+            }
+            (gps.unwrap().to_owned(), acc.unwrap().to_owned())
+        };
+        let gps_file = z.by_name(&gps_path).context("failed to get .gps file from archive")?;
+        let records = parse_gps_records(std::io::BufReader::new(gps_file))?;
+
+        let acc_file = z.by_name(&acc_path).context("failed to get .acc file from archive")?;
+        let acc_records = acc::parse(std::io::BufReader::new(acc_file))?;
+        (records, acc_records)
+    };
+
     let max_speed = records
         .iter()
         .filter_map(|r| match r {
@@ -166,10 +233,59 @@ fn main() -> Result<()> {
         }
     }
 
-    println!("writing to out.gpx");
-    gpx::write_gpx(
-        File::create("out.gpx")?,
-        &[&points[..]])?;
+    if let Some((lat, lon, radius_m)) = geofence {
+        let before = points.len();
+        points = track::geofence(&points, lat, lon, radius_m);
+        println!("geofence kept {} of {} points", points.len(), before);
+    }
+
+    let fused = acc::fuse(&points, &acc_records);
+    let events: Vec<_> = fused.iter()
+        .filter_map(|f| f.event.map(|event| (f.point.time, event)))
+        .collect();
+    println!("detected {} hard-braking/sudden-acceleration events", events.len());
+    for (time, event) in &events {
+        println!("  {:?} at {}", event, time);
+    }
+
+    let segments = track::segment_by_gap(&points, segment_gap);
+    let stats = track::compute_stats(&segments);
+    println!("{} segment(s)", segments.len());
+    println!("distance: {:.1} km", stats.distance_m / 1000.);
+    println!("ascent: {:.0} m, descent: {:.0} m", stats.ascent_m, stats.descent_m);
+    println!(
+        "moving time: {}, total time: {}",
+        format_duration(stats.moving_time), format_duration(stats.total_time));
+
+    let format = formats::by_name(&format_name)?;
+    let out_path = format!("out.{}", format.extension());
+    println!("writing to {}", out_path);
+    let fused_segments = track::segment_fused_by_gap(&fused, segment_gap);
+    let fused_segment_slices: Vec<&[acc::FusedPoint]> = fused_segments.iter().map(|s| s.as_slice()).collect();
+    format.write(&mut File::create(&out_path)?, &fused_segment_slices)?;
+
+    match subcommand.as_deref() {
+        Some("geotag") => {
+            let dir = args.next().expect("geotag needs a photo directory");
+            let tolerance = args.next()
+                .map(|s| s.parse().map(chrono::Duration::seconds).context("invalid tolerance"))
+                .transpose()?
+                .map(geotag::Tolerance)
+                .unwrap_or_default();
+            geotag::geotag_directory(std::path::Path::new(&dir), &points, tolerance)?;
+        }
+        Some("mp4") => {
+            let mp4_path = args.next().expect("mp4 needs a video file path");
+            let mut mp4_file = std::fs::OpenOptions::new()
+                .read(true).write(true)
+                .open(&mp4_path)
+                .context("failed to open MP4 file")?;
+            mp4::write_mp4_gps(&mut mp4_file, &points)?;
+            println!("embedded GPS track into {}", mp4_path);
+        }
+        Some(other) => bail!("unrecognized subcommand {}", other),
+        None => {}
+    }
 
     Ok(())
 }
@@ -0,0 +1,247 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::prelude::*;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use crate::gpx::Point;
+
+const GPS_BOX_TYPE: &[u8; 4] = b"gps ";
+
+/// One fixed-size serialized GPS sample: timestamp (ms since epoch), lat,
+/// lon, ele, speed, course, all as big-endian values.
+const SAMPLE_SIZE: usize = 8 + 8 * 5;
+
+fn read_u32(data: &[u8], off: usize) -> u32 {
+    u32::from_be_bytes(data[off..off + 4].try_into().unwrap())
+}
+
+fn write_u32(data: &mut [u8], off: usize, v: u32) {
+    data[off..off + 4].copy_from_slice(&v.to_be_bytes());
+}
+
+fn read_u64(data: &[u8], off: usize) -> u64 {
+    u64::from_be_bytes(data[off..off + 8].try_into().unwrap())
+}
+
+fn write_u64(data: &mut [u8], off: usize, v: u64) {
+    data[off..off + 8].copy_from_slice(&v.to_be_bytes());
+}
+
+/// Box types that just contain a back-to-back sequence of child boxes, so
+/// it's safe to recurse into them looking for `stco`/`co64`.
+const CONTAINER_BOX_TYPES: &[&[u8; 4]] = &[
+    b"moov", b"trak", b"mdia", b"minf", b"stbl", b"udta", b"edts", b"dinf", b"mvex", b"moof", b"traf",
+];
+
+/// Walk every `stco`/`co64` chunk-offset table nested under `[start, end)`
+/// and add `delta` to any absolute file offset at or past `threshold`.
+///
+/// `stco`/`co64` store sample data locations as absolute byte offsets into
+/// the file; when bytes are inserted earlier in the file (e.g. a new box
+/// appended inside `moov` while `mdat` sits after it), every one of those
+/// offsets needs to move by the same amount or playback reads garbage.
+fn patch_chunk_offsets(data: &mut [u8], start: usize, end: usize, threshold: usize, delta: i64) {
+    let mut pos = start;
+    while pos + 8 <= end {
+        let size = read_u32(data, pos) as usize;
+        if size < 8 || pos + size > end {
+            break;
+        }
+        let box_type: [u8; 4] = data[pos + 4..pos + 8].try_into().unwrap();
+        if &box_type == b"stco" {
+            patch_stco_entries(data, pos, size, threshold, delta, false);
+        } else if &box_type == b"co64" {
+            patch_stco_entries(data, pos, size, threshold, delta, true);
+        } else if CONTAINER_BOX_TYPES.iter().any(|t| **t == box_type) {
+            patch_chunk_offsets(data, pos + 8, pos + size, threshold, delta);
+        }
+        pos += size;
+    }
+}
+
+/// `stco`/`co64` layout: box header(8) + version/flags(4) + entry_count(4)
+/// + entry_count * (4 or 8)-byte absolute offsets.
+fn patch_stco_entries(data: &mut [u8], box_off: usize, box_size: usize, threshold: usize, delta: i64, is64: bool) {
+    let entries_start = box_off + 8 + 4 + 4;
+    let count = read_u32(data, box_off + 8 + 4) as usize;
+    let entry_size = if is64 { 8 } else { 4 };
+    for i in 0..count {
+        let off = entries_start + i * entry_size;
+        if off + entry_size > box_off + box_size {
+            break;
+        }
+        if is64 {
+            let v = read_u64(data, off);
+            if v as usize >= threshold {
+                write_u64(data, off, (v as i64 + delta) as u64);
+            }
+        } else {
+            let v = read_u32(data, off);
+            if v as usize >= threshold {
+                write_u32(data, off, (v as i64 + delta) as u32);
+            }
+        }
+    }
+}
+
+/// Walk top-level boxes (size:u32, type:[u8;4], ...) looking for `want`.
+/// Doesn't handle the 64-bit "largesize" extension, since none of this
+/// tool's inputs produce boxes that large.
+fn find_top_level_box(data: &[u8], want: &[u8; 4]) -> Option<usize> {
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let size = read_u32(data, pos) as usize;
+        if size < 8 || pos + size > data.len() {
+            return None;
+        }
+        if &data[pos + 4..pos + 8] == want {
+            return Some(pos);
+        }
+        pos += size;
+    }
+    None
+}
+
+fn serialize_sample(point: &Point) -> [u8; SAMPLE_SIZE] {
+    let mut buf = [0u8; SAMPLE_SIZE];
+    buf[0..8].copy_from_slice(&point.time.timestamp_millis().to_be_bytes());
+    buf[8..16].copy_from_slice(&point.lat.to_be_bytes());
+    buf[16..24].copy_from_slice(&point.lon.to_be_bytes());
+    buf[24..32].copy_from_slice(&point.ele.to_be_bytes());
+    buf[32..40].copy_from_slice(&point.speed.to_be_bytes());
+    buf[40..48].copy_from_slice(&point.course.to_be_bytes());
+    buf
+}
+
+/// Build a `gps ` box: a `version_and_date` header followed by a table of
+/// (offset, size) descriptors, one per sample, pointing into the sample data
+/// that follows the table - modeled on the data-block layout dashcam
+/// firmware uses to store a GPS track alongside the footage.
+fn build_gps_box(points: &[Point], written_at: DateTime<Utc>) -> Vec<u8> {
+    const VERSION: u32 = 1;
+    let table_len = points.len() * 8; // (u32 offset, u32 size) per descriptor
+    let data_start = 8 /* version_and_date */ + table_len;
+
+    let mut table = Vec::with_capacity(table_len);
+    let mut data = Vec::with_capacity(points.len() * SAMPLE_SIZE);
+    let mut offset = data_start as u32;
+    for point in points {
+        let sample = serialize_sample(point);
+        table.extend_from_slice(&offset.to_be_bytes());
+        table.extend_from_slice(&(sample.len() as u32).to_be_bytes());
+        data.extend_from_slice(&sample);
+        offset += sample.len() as u32;
+    }
+
+    let version_and_date = ((VERSION as u64) << 32) | written_at.timestamp() as u64;
+
+    let mut payload = Vec::with_capacity(8 + table.len() + data.len());
+    payload.extend_from_slice(&version_and_date.to_be_bytes());
+    payload.extend_from_slice(&table);
+    payload.extend_from_slice(&data);
+
+    let box_size = 8 + payload.len();
+    let mut gps_box = Vec::with_capacity(box_size);
+    gps_box.extend_from_slice(&(box_size as u32).to_be_bytes());
+    gps_box.extend_from_slice(GPS_BOX_TYPE);
+    gps_box.extend_from_slice(&payload);
+    gps_box
+}
+
+/// Append `points` to an existing MP4 as a `gps ` box nested directly inside
+/// `moov`, patching `moov`'s size field to account for the new child.
+pub fn write_mp4_gps(mp4: &mut File, points: &[Point]) -> Result<()> {
+    let mut data = Vec::new();
+    mp4.seek(SeekFrom::Start(0)).context("failed to seek MP4 file")?;
+    mp4.read_to_end(&mut data).context("failed to read MP4 file")?;
+
+    let moov_off = find_top_level_box(&data, b"moov")
+        .ok_or_else(|| anyhow!("no moov box found in MP4 file"))?;
+    let moov_size = read_u32(&data, moov_off) as usize;
+    let insert_at = moov_off + moov_size;
+
+    let gps_box = build_gps_box(points, Utc::now());
+    let delta = gps_box.len() as i64;
+
+    // If `mdat` sits after `moov` (the common "faststart" layout), inserting
+    // our box inside moov shifts mdat forward, so every absolute chunk
+    // offset in moov's sample tables that points into mdat has to move too.
+    if let Some(mdat_off) = find_top_level_box(&data, b"mdat") {
+        if mdat_off > moov_off {
+            patch_chunk_offsets(&mut data, moov_off + 8, insert_at, insert_at, delta);
+        }
+    }
+
+    data.splice(insert_at..insert_at, gps_box.iter().copied());
+
+    write_u32(&mut data, moov_off, (moov_size + gps_box.len()) as u32);
+
+    mp4.set_len(0).context("failed to truncate MP4 file")?;
+    mp4.seek(SeekFrom::Start(0)).context("failed to seek MP4 file")?;
+    mp4.write_all(&data).context("failed to write MP4 file")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(lat: f64, lon: f64, ele: f64) -> Point {
+        Point {
+            time: Utc.timestamp(1_000, 0).into(),
+            lat, lon, ele,
+            speed: 1.5,
+            course: 90.,
+        }
+    }
+
+    #[test]
+    fn serialize_sample_is_big_endian() {
+        let p = point(1., 2., 3.);
+        let buf = serialize_sample(&p);
+        assert_eq!(i64::from_be_bytes(buf[0..8].try_into().unwrap()), p.time.timestamp_millis());
+        assert_eq!(f64::from_be_bytes(buf[8..16].try_into().unwrap()), p.lat);
+        assert_eq!(f64::from_be_bytes(buf[16..24].try_into().unwrap()), p.lon);
+        assert_eq!(f64::from_be_bytes(buf[24..32].try_into().unwrap()), p.ele);
+        assert_eq!(f64::from_be_bytes(buf[32..40].try_into().unwrap()), p.speed);
+        assert_eq!(f64::from_be_bytes(buf[40..48].try_into().unwrap()), p.course);
+    }
+
+    #[test]
+    fn build_gps_box_header_and_table() {
+        let points = vec![point(1., 2., 3.), point(4., 5., 6.)];
+        let written_at = Utc.timestamp(123_456, 0);
+        let gps_box = build_gps_box(&points, written_at);
+
+        let box_size = read_u32(&gps_box, 0) as usize;
+        assert_eq!(box_size, gps_box.len());
+        assert_eq!(&gps_box[4..8], GPS_BOX_TYPE);
+
+        let version_and_date = read_u64(&gps_box, 8);
+        assert_eq!(version_and_date >> 32, 1);
+        assert_eq!(version_and_date as u32 as i64, written_at.timestamp());
+
+        // Descriptor table starts right after the box header + version/date.
+        let table_off = 16;
+        let first_offset = read_u32(&gps_box, table_off) as usize;
+        let first_size = read_u32(&gps_box, table_off + 4) as usize;
+        assert_eq!(first_size, SAMPLE_SIZE);
+        assert_eq!(first_offset, 8 + points.len() * 8);
+        let second_offset = read_u32(&gps_box, table_off + 8) as usize;
+        assert_eq!(second_offset, first_offset + SAMPLE_SIZE);
+    }
+
+    #[test]
+    fn find_top_level_box_locates_and_skips() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&16u32.to_be_bytes());
+        data.extend_from_slice(b"ftyp");
+        data.extend_from_slice(&[0u8; 8]);
+        let moov_off = data.len();
+        data.extend_from_slice(&8u32.to_be_bytes());
+        data.extend_from_slice(b"moov");
+
+        assert_eq!(find_top_level_box(&data, b"moov"), Some(moov_off));
+        assert_eq!(find_top_level_box(&data, b"mdat"), None);
+    }
+}
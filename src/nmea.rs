@@ -0,0 +1,272 @@
+use anyhow::{bail, Context, Result};
+use chrono::prelude::*;
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use crate::GpsRecord;
+use crate::OptionExt;
+
+/// True if `first_line` looks like an NMEA 0183 sentence rather than the
+/// proprietary comma-tagged `.gps` format, so callers can pick an input mode
+/// without the caller having to know about file extensions or zip contents.
+pub fn sniff(first_line: &str) -> bool {
+    first_line.trim_start().starts_with('$')
+}
+
+struct Fix {
+    time: NaiveTime,
+    date: Option<NaiveDate>,
+    lat: f64,
+    lon: f64,
+    ele: Option<f64>,
+    speed: Option<f64>, // m/s
+    course: Option<f64>, // degrees
+}
+
+/// Parse a plain NMEA 0183 log (as produced by a cheap USB/serial GPS) into
+/// the same `GpsRecord` stream the rest of `main()` consumes: the first valid
+/// fix becomes a `Coords` record, and every later fix a `Delta` against it.
+pub fn parse(reader: impl BufRead) -> Result<Vec<GpsRecord>> {
+    let lines: Vec<String> = reader.lines().collect::<std::io::Result<_>>().context("read error")?;
+
+    let mut altitudes: HashMap<NaiveTime, f64> = HashMap::new();
+    for line in &lines {
+        let line = line.trim();
+        if line.is_empty() || !matches!(sentence_tag(line), Some("GPGGA") | Some("GNGGA")) {
+            continue;
+        }
+        let body = verify_checksum(line)?;
+        let mut fields = body.split(',');
+        fields.next(); // tag, already matched above
+        if let Some((time, ele)) = parse_gga(fields)? {
+            altitudes.insert(time, ele);
+        }
+    }
+
+    let mut fixes = vec![];
+    for line in &lines {
+        let line = line.trim();
+        if line.is_empty() || !matches!(sentence_tag(line), Some("GPRMC") | Some("GNRMC")) {
+            continue;
+        }
+        let body = verify_checksum(line)?;
+        let mut fields = body.split(',');
+        fields.next(); // tag, already matched above
+        if let Some(mut fix) = parse_rmc(fields)? {
+            fix.ele = altitudes.get(&fix.time).copied();
+            fixes.push(fix);
+        }
+    }
+
+    let mut records = vec![];
+    let mut base: Option<(DateTime<FixedOffset>, f64, f64, f64)> = None;
+    for fix in fixes {
+        let Some(date) = fix.date else { continue };
+        let timestamp: DateTime<FixedOffset> =
+            DateTime::<Utc>::from_utc(NaiveDateTime::new(date, fix.time), Utc).into();
+        let ele = fix.ele.unwrap_or(0.);
+        match base {
+            None => {
+                records.push(GpsRecord::Coords { timestamp, lat: fix.lat, lon: fix.lon, ele });
+                base = Some((timestamp, fix.lat, fix.lon, ele));
+            }
+            Some((base_time, base_lat, base_lon, base_ele)) => {
+                records.push(GpsRecord::Delta {
+                    duration: timestamp - base_time,
+                    lat: fix.lat - base_lat,
+                    lon: fix.lon - base_lon,
+                    ele: ele - base_ele,
+                    speed: fix.speed.unwrap_or(0.),
+                    heading: fix.course.unwrap_or(0.),
+                });
+            }
+        }
+    }
+    Ok(records)
+}
+
+/// Extract a sentence's tag (e.g. `"GPRMC"`) without checksum-verifying it,
+/// so callers can decide whether a line is even worth validating - real logs
+/// interleave plenty of sentence types (and the occasional noise line from a
+/// serial glitch) this tool has no use for.
+fn sentence_tag(line: &str) -> Option<&str> {
+    let body = line.strip_prefix('$')?.split_once('*')?.0;
+    body.split(',').next()
+}
+
+/// Strip the leading `$` and trailing `*hh`, verifying the XOR checksum.
+fn verify_checksum(line: &str) -> Result<&str> {
+    let line = line.strip_prefix('$')
+        .ok_or_else(|| anyhow::anyhow!("NMEA sentence missing leading $"))?;
+    let (body, checksum) = line.split_once('*')
+        .ok_or_else(|| anyhow::anyhow!("NMEA sentence missing checksum"))?;
+    let expected = u8::from_str_radix(checksum.trim(), 16).context("invalid NMEA checksum")?;
+    let actual = body.bytes().fold(0u8, |acc, b| acc ^ b);
+    if actual != expected {
+        bail!("NMEA checksum mismatch in {:?} (expected {:02X}, got {:02X})", line, expected, actual);
+    }
+    Ok(body)
+}
+
+fn parse_nmea_time(s: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(s, "%H%M%S%.f").context("invalid NMEA time")
+}
+
+fn parse_nmea_date(s: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(s, "%d%m%y").context("invalid NMEA date")
+}
+
+fn parse_nmea_coord(val: &str, dir: &str) -> Result<f64> {
+    let raw: f64 = val.parse().context("invalid NMEA coordinate")?;
+    let degrees = (raw / 100.).floor();
+    let minutes = raw - degrees * 100.;
+    let decimal = degrees + minutes / 60.;
+    match dir {
+        "N" | "E" => Ok(decimal),
+        "S" | "W" => Ok(-decimal),
+        _ => bail!("invalid NMEA coordinate direction {}", dir),
+    }
+}
+
+/// `$G.RMC,time,status,lat,N/S,lon,E/W,speed(knots),course,date,...`
+fn parse_rmc<'a>(mut fields: impl Iterator<Item = &'a str>) -> Result<Option<Fix>> {
+    let time_str = fields.next().named("time")?;
+    let status = fields.next().named("status")?;
+    if status != "A" {
+        return Ok(None); // no valid fix yet
+    }
+    let lat_str = fields.next().named("latitude")?;
+    let lat_dir = fields.next().named("latitude direction")?;
+    let lon_str = fields.next().named("longitude")?;
+    let lon_dir = fields.next().named("longitude direction")?;
+    let speed_knots: f64 = fields.next().named("speed")?.parse().context("invalid speed")?;
+    let course: f64 = fields.next().named("course")?.parse().unwrap_or(0.);
+    let date_str = fields.next().named("date")?;
+
+    Ok(Some(Fix {
+        time: parse_nmea_time(time_str)?,
+        date: Some(parse_nmea_date(date_str)?),
+        lat: parse_nmea_coord(lat_str, lat_dir)?,
+        lon: parse_nmea_coord(lon_str, lon_dir)?,
+        ele: None,
+        speed: Some(speed_knots * 0.514444),
+        course: Some(course),
+    }))
+}
+
+/// `$G.GGA,time,lat,N/S,lon,E/W,fix_quality,num_sat,hdop,altitude,M,...`
+fn parse_gga<'a>(mut fields: impl Iterator<Item = &'a str>) -> Result<Option<(NaiveTime, f64)>> {
+    let time_str = fields.next().named("time")?;
+    let _lat = fields.next();
+    let _lat_dir = fields.next();
+    let _lon = fields.next();
+    let _lon_dir = fields.next();
+    let fix_quality: u32 = fields.next().named("fix quality")?.parse().unwrap_or(0);
+    if fix_quality == 0 {
+        return Ok(None); // no fix
+    }
+    let _num_sat = fields.next();
+    let _hdop = fields.next();
+    let altitude: f64 = fields.next().named("altitude")?.parse().context("invalid altitude")?;
+
+    Ok(Some((parse_nmea_time(time_str)?, altitude)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a well-formed `$body*hh` sentence with a correct checksum, so
+    /// tests don't need to hand-compute XOR checksums.
+    fn with_checksum(body: &str) -> String {
+        let checksum = body.bytes().fold(0u8, |acc, b| acc ^ b);
+        format!("${}*{:02X}", body, checksum)
+    }
+
+    #[test]
+    fn parse_nmea_coord_converts_degrees_minutes() {
+        let lat = parse_nmea_coord("4807.038", "N").unwrap();
+        assert!((lat - 48.1173).abs() < 1e-4);
+        let lat = parse_nmea_coord("4807.038", "S").unwrap();
+        assert!((lat + 48.1173).abs() < 1e-4);
+    }
+
+    #[test]
+    fn parse_nmea_coord_rejects_bad_direction() {
+        assert!(parse_nmea_coord("4807.038", "Q").is_err());
+    }
+
+    #[test]
+    fn parse_nmea_date_and_time() {
+        assert_eq!(parse_nmea_date("230394").unwrap(), NaiveDate::from_ymd(1994, 3, 23));
+        assert_eq!(parse_nmea_time("123519").unwrap(), NaiveTime::from_hms(12, 35, 19));
+    }
+
+    #[test]
+    fn sentence_tag_extracts_tag() {
+        let line = with_checksum("GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W");
+        assert_eq!(sentence_tag(&line), Some("GPRMC"));
+    }
+
+    #[test]
+    fn sentence_tag_none_for_malformed_line() {
+        assert_eq!(sentence_tag("not an nmea sentence"), None);
+        assert_eq!(sentence_tag("$GPRMC,no,checksum,here"), None);
+    }
+
+    #[test]
+    fn verify_checksum_accepts_valid_and_rejects_mismatch() {
+        let good = with_checksum("GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W");
+        assert!(verify_checksum(&good).is_ok());
+
+        let bad = "$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*00";
+        assert!(verify_checksum(bad).is_err());
+    }
+
+    #[test]
+    fn parse_reconstructs_coords_and_delta_from_two_fixes() {
+        let rmc1 = with_checksum("GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W");
+        let rmc2 = with_checksum("GPRMC,123520,A,4808.038,N,01131.000,E,022.4,084.4,230394,003.1,W");
+        let log = format!("{}\n{}\n", rmc1, rmc2);
+        let records = parse(log.as_bytes()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(matches!(records[0], GpsRecord::Coords { .. }));
+        match &records[1] {
+            GpsRecord::Delta { duration, lat, .. } => {
+                assert_eq!(*duration, chrono::Duration::seconds(1));
+                assert!(*lat > 0.);
+            }
+            other => panic!("expected Delta, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_skips_unrelated_sentences_with_bad_checksums() {
+        let rmc = with_checksum("GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W");
+        // a GPGSV line with a deliberately wrong checksum, and a noise line
+        // with no checksum at all - neither is parsed, so neither should
+        // abort the import.
+        let log = format!("$GPGSV,3,1,11,10,63,137,17*00\n{}\nnot nmea at all\n", rmc);
+        let records = parse(log.as_bytes()).unwrap();
+        assert_eq!(records.len(), 1);
+        assert!(matches!(records[0], GpsRecord::Coords { .. }));
+    }
+
+    #[test]
+    fn parse_bails_on_bad_checksum_for_a_sentence_it_does_use() {
+        let log = "$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*00\n";
+        assert!(parse(log.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn parse_merges_gga_altitude_into_matching_rmc_fix() {
+        let gga = with_checksum("GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,");
+        let rmc = with_checksum("GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W");
+        let log = format!("{}\n{}\n", gga, rmc);
+        let records = parse(log.as_bytes()).unwrap();
+        match &records[0] {
+            GpsRecord::Coords { ele, .. } => assert_eq!(*ele, 545.4),
+            other => panic!("expected Coords, got {:?}", other),
+        }
+    }
+}
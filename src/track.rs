@@ -0,0 +1,171 @@
+use chrono::Duration;
+
+use crate::acc::FusedPoint;
+use crate::gpx::Point;
+
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+fn haversine_m(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let dlat = (lat2 - lat1).to_radians();
+    let dlon = (lon2 - lon1).to_radians();
+    let h = (dlat / 2.).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (dlon / 2.).sin().powi(2);
+    2. * EARTH_RADIUS_M * h.sqrt().asin()
+}
+
+pub fn haversine_distance_m(a: &Point, b: &Point) -> f64 {
+    haversine_m(a.lat, a.lon, b.lat, b.lon)
+}
+
+/// Split `points` into segments wherever the time gap between consecutive
+/// points exceeds `gap` - a "stopped" heuristic, since the device keeps
+/// logging through a long pause but we don't want one track stitched
+/// straight through it.
+pub fn segment_by_gap(points: &[Point], gap: Duration) -> Vec<Vec<Point>> {
+    let mut segments = vec![];
+    let mut current: Vec<Point> = vec![];
+    for point in points {
+        if let Some(last) = current.last() {
+            if point.time - last.time > gap {
+                segments.push(std::mem::take(&mut current));
+            }
+        }
+        current.push(point.clone());
+    }
+    if !current.is_empty() {
+        segments.push(current);
+    }
+    segments
+}
+
+/// Same grouping as `segment_by_gap`, but for the accelerometer-fused stream,
+/// so event/accel data can flow through to the output writers alongside it.
+pub fn segment_fused_by_gap(points: &[FusedPoint], gap: Duration) -> Vec<Vec<FusedPoint>> {
+    let mut segments = vec![];
+    let mut current: Vec<FusedPoint> = vec![];
+    for point in points {
+        if let Some(last) = current.last() {
+            if point.point.time - last.point.time > gap {
+                segments.push(std::mem::take(&mut current));
+            }
+        }
+        current.push(point.clone());
+    }
+    if !current.is_empty() {
+        segments.push(current);
+    }
+    segments
+}
+
+/// Minimum speed, in meters per second, above which a point counts as "moving".
+const MOVING_SPEED_THRESHOLD: f64 = 0.5;
+
+#[derive(Debug)]
+pub struct TrackStats {
+    pub distance_m: f64,
+    pub ascent_m: f64,
+    pub descent_m: f64,
+    pub moving_time: Duration,
+    pub total_time: Duration,
+}
+
+pub fn compute_stats(segments: &[Vec<Point>]) -> TrackStats {
+    let mut stats = TrackStats {
+        distance_m: 0.,
+        ascent_m: 0.,
+        descent_m: 0.,
+        moving_time: Duration::zero(),
+        total_time: Duration::zero(),
+    };
+    for segment in segments {
+        for pair in segment.windows(2) {
+            let (p0, p1) = (&pair[0], &pair[1]);
+            stats.distance_m += haversine_distance_m(p0, p1);
+            let ele_delta = p1.ele - p0.ele;
+            if ele_delta > 0. {
+                stats.ascent_m += ele_delta;
+            } else {
+                stats.descent_m += -ele_delta;
+            }
+            if p1.speed >= MOVING_SPEED_THRESHOLD {
+                stats.moving_time += p1.time - p0.time;
+            }
+        }
+        if let (Some(first), Some(last)) = (segment.first(), segment.last()) {
+            stats.total_time += last.time - first.time;
+        }
+    }
+    stats
+}
+
+/// Keep only points within `radius_m` meters of `(center_lat, center_lon)`.
+pub fn geofence(points: &[Point], center_lat: f64, center_lon: f64, radius_m: f64) -> Vec<Point> {
+    points.iter()
+        .filter(|p| haversine_m(center_lat, center_lon, p.lat, p.lon) <= radius_m)
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::prelude::*;
+
+    fn point_at(secs: i64, lat: f64, lon: f64, ele: f64, speed: f64) -> Point {
+        Point { time: Utc.timestamp(secs, 0).into(), lat, lon, ele, speed, course: 0. }
+    }
+
+    #[test]
+    fn haversine_distance_m_same_point_is_zero() {
+        let a = point_at(0, 40.0, -73.0, 0., 0.);
+        assert_eq!(haversine_distance_m(&a, &a), 0.);
+    }
+
+    #[test]
+    fn haversine_distance_m_one_degree_longitude_at_equator() {
+        // 1 degree of longitude at the equator is ~111.32 km.
+        let a = point_at(0, 0., 0., 0., 0.);
+        let b = point_at(0, 0., 1., 0., 0.);
+        let dist = haversine_distance_m(&a, &b);
+        assert!((dist - 111_320.).abs() < 500., "distance was {}", dist);
+    }
+
+    #[test]
+    fn segment_by_gap_splits_on_large_time_gaps() {
+        let points = vec![
+            point_at(0, 0., 0., 0., 0.),
+            point_at(60, 0., 0., 0., 0.),
+            point_at(600, 0., 0., 0., 0.), // 9 minute gap
+        ];
+        let segments = segment_by_gap(&points, Duration::seconds(120));
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].len(), 2);
+        assert_eq!(segments[1].len(), 1);
+    }
+
+    #[test]
+    fn geofence_keeps_only_points_within_radius() {
+        let points = vec![
+            point_at(0, 0., 0., 0., 0.),
+            point_at(0, 10., 10., 0., 0.), // far away
+        ];
+        let kept = geofence(&points, 0., 0., 1000.);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].lat, 0.);
+    }
+
+    #[test]
+    fn compute_stats_accumulates_distance_elevation_and_time() {
+        let segments = vec![vec![
+            point_at(0, 0., 0., 100., 1.0),
+            point_at(10, 0., 0.001, 110., 1.0), // ~111m east, climbing, moving
+            point_at(20, 0., 0.001, 90., 0.0),  // same spot, descending, stopped
+        ]];
+        let stats = compute_stats(&segments);
+        assert!(stats.distance_m > 0.);
+        assert_eq!(stats.ascent_m, 10.);
+        assert_eq!(stats.descent_m, 20.);
+        assert_eq!(stats.moving_time, Duration::seconds(10));
+        assert_eq!(stats.total_time, Duration::seconds(20));
+    }
+}